@@ -0,0 +1,65 @@
+use std::alloc::{self, Layout};
+
+/// A pluggable backing-storage strategy for `CompactVec`'s "free" (non-compact)
+/// heap storage. Kept as a trait so compact collections can eventually be
+/// pointed at arena- or pool-backed storage instead of the global heap.
+pub trait Allocator {
+    /// Allocate storage for `count` elements of `T`, aborting the process
+    /// via `handle_alloc_error` (matching `Vec`'s own behavior on OOM) if the
+    /// allocator can't satisfy the request, and panicking with "capacity
+    /// overflow" if the size of the request can't be computed.
+    fn allocate<T>(count: usize) -> *mut T {
+        match Self::try_allocate::<T>(count) {
+            Some(ptr) => ptr,
+            None => match layout_for::<T>(count) {
+                // the allocator itself rejected a well-formed request
+                Some(layout) => alloc::handle_alloc_error(layout),
+                // the request was malformed (layout computation overflowed)
+                // to begin with, so there's no Layout to hand to the
+                // allocator-error hook
+                None => panic!("capacity overflow"),
+            },
+        }
+    }
+
+    /// Allocate storage for `count` elements of `T`, returning `None`
+    /// instead of aborting if the request can't be satisfied. This is the
+    /// primitive `CompactVec`'s `try_*` family builds on.
+    fn try_allocate<T>(count: usize) -> Option<*mut T>;
+
+    /// Deallocate storage previously returned by `allocate`/`try_allocate`
+    /// for the same element type and `count`.
+    unsafe fn deallocate<T>(ptr: *mut T, count: usize);
+}
+
+/// Compute the `Layout` for `count` elements of `T`, returning `None`
+/// instead of panicking if that computation overflows (e.g. `count *
+/// size_of::<T>() > isize::MAX`), so fallible callers can turn it into a
+/// `TryReserveError` rather than aborting.
+fn layout_for<T>(count: usize) -> Option<Layout> {
+    Layout::array::<T>(count).ok()
+}
+
+/// The default `Allocator`, backed by the process's global heap allocator.
+pub struct DefaultHeap;
+
+impl Allocator for DefaultHeap {
+    fn try_allocate<T>(count: usize) -> Option<*mut T> {
+        let layout = layout_for::<T>(count)?;
+        if layout.size() == 0 {
+            return Some(layout.align() as *mut T);
+        }
+
+        let ptr = unsafe { alloc::alloc(layout) } as *mut T;
+        if ptr.is_null() { None } else { Some(ptr) }
+    }
+
+    unsafe fn deallocate<T>(ptr: *mut T, count: usize) {
+        // `count` was already a valid layout when this storage was
+        // allocated, so recomputing it here can't overflow
+        let layout = layout_for::<T>(count).expect("capacity overflow");
+        if layout.size() != 0 {
+            alloc::dealloc(ptr as *mut u8, layout);
+        }
+    }
+}